@@ -0,0 +1,84 @@
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use irc::client::{prelude::Config as IrcConfig, Client as IrcClient, ClientStream};
+use tokio::sync::mpsc;
+
+use crate::{
+    message::{ChannelId, Message},
+    task::{Task, TaskId},
+};
+
+/// Bridges IRC channels into the supervisor's internal message bus. Mirrors
+/// `MatrixTask`: owns the IRC connection loop, translates raw IRC messages
+/// into `Message`s, and delivers anything routed to it by sending a PRIVMSG.
+pub struct IrcTask {
+    config: IrcConfig,
+}
+
+impl IrcTask {
+    pub fn new(config: IrcConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Task for IrcTask {
+    async fn start(
+        &self,
+        id: TaskId,
+        tx: mpsc::Sender<Message>,
+        mut rx: mpsc::Receiver<Message>,
+    ) -> anyhow::Result<()> {
+        let mut client = IrcClient::from_config(self.config.clone()).await?;
+        client.identify()?;
+
+        let sender = client.sender();
+        let mut stream: ClientStream = client.stream()?;
+
+        loop {
+            tokio::select! {
+                incoming = stream.next() => {
+                    let Some(message) = incoming else {
+                        break;
+                    };
+                    let message = message?;
+                    if let Some(translated) = translate_incoming(&message) {
+                        let _ = tx.send(translated).await;
+                    }
+                }
+                outgoing = rx.recv() => {
+                    let Some(outgoing) = outgoing else {
+                        break;
+                    };
+                    let ChannelId::Irc(channel) = &outgoing.origin else {
+                        continue;
+                    };
+                    let line = format!("{}: {}", outgoing.sender, outgoing.body);
+                    if let Err(err) = sender.send_privmsg(channel, line) {
+                        eprintln!("[{id}] Failed to send to {channel}: {err:?}");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Translates a raw IRC `PRIVMSG` into an internal `Message`; everything
+/// else (joins, pings, ...) is handled by the `irc` crate itself and is
+/// irrelevant to routing.
+fn translate_incoming(message: &irc::proto::Message) -> Option<Message> {
+    let irc::proto::Command::PRIVMSG(ref target, ref body) = message.command else {
+        return None;
+    };
+    let sender = message.source_nickname()?.to_owned();
+
+    Some(Message {
+        origin: ChannelId::Irc(target.clone()),
+        link: target.clone(),
+        body: body.clone(),
+        sender,
+        attachment: None,
+    })
+}
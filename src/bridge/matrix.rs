@@ -0,0 +1,881 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    config::SyncSettings,
+    media::{MediaFormat, MediaRequest},
+    room::{Room, RoomMember},
+    ruma::{
+        events::room::{
+            member::StrippedRoomMemberEvent,
+            message::{
+                MessageType, OriginalSyncRoomMessageEvent, RoomMessageEventContent,
+                TextMessageEventContent,
+            },
+            MediaSource,
+        },
+        OwnedRoomId, OwnedUserId, RoomAliasId, RoomId,
+    },
+    Client, Error,
+};
+use tokio::{
+    sync::{mpsc, Mutex, RwLock},
+    time::{sleep, Duration},
+};
+use url::Url;
+
+use crate::{
+    config::{Config, LinkEntry, Session},
+    event::{decide, Action, Event},
+    linkmap::LinkMap,
+    message::{Attachment, AttachmentKind, ChannelId, Message},
+    task::{Task, TaskId},
+};
+
+/// Power level an existing Matrix room member needs before `!relay`/
+/// `!unrelay` will act on their command; this matches the default
+/// "moderator" level most homeservers ship with.
+const ADMIN_POWER_LEVEL: i64 = 50;
+
+/// Bridges Matrix rooms into the supervisor's internal message bus. Owns the
+/// sync loop; everything else (routing, commands) lives outside of it.
+pub struct MatrixTask {
+    client: Client,
+    own_user_id: OwnedUserId,
+    rooms: Arc<Mutex<HashMap<OwnedRoomId, Room>>>,
+    link_map: Arc<RwLock<LinkMap<ChannelId>>>,
+    config: Arc<Mutex<Config>>,
+    config_path: PathBuf,
+}
+
+impl MatrixTask {
+    /// Logs in with a password, writes the resulting `Session` to disk via
+    /// the caller, and opens the crypto store at `crypto_store_path` so
+    /// subsequent runs can restore this login and join encrypted rooms.
+    pub async fn login(
+        homeserver_url: String,
+        username: &str,
+        password: &str,
+        crypto_store_path: &Path,
+    ) -> anyhow::Result<Session> {
+        let client = build_client(&homeserver_url, crypto_store_path).await?;
+
+        client
+            .login_username(username, password)
+            .initial_device_display_name("matrix-relay-bot")
+            .send()
+            .await?;
+
+        let session = client
+            .session()
+            .await
+            .expect("session must be set immediately after a successful login");
+
+        Ok(Session {
+            access_token: session.access_token,
+            user_id: session.user_id.to_string(),
+            device_id: session.device_id.to_string(),
+        })
+    }
+
+    /// Restores a previously persisted session (skipping the full
+    /// re-sync/login a fresh `login_username` would force), runs an initial
+    /// sync, and builds the initial `LinkMap` from whatever `!relay` links
+    /// were persisted in `config` plus the Tor<->clearnet room groups
+    /// discovered via room-name matching (see `get_room_groups`). Returns
+    /// the task plus a shared handle to the
+    /// `LinkMap`, so the caller can hand it to the supervisor while
+    /// `!relay`/`!unrelay` keep mutating the same instance.
+    pub async fn connect(
+        config: Config,
+        config_path: PathBuf,
+        crypto_store_path: &Path,
+    ) -> anyhow::Result<(Self, Arc<RwLock<LinkMap<ChannelId>>>)> {
+        let session = config
+            .session
+            .clone()
+            .expect("caller already checked config.session is set");
+
+        let client = build_client(&config.homeserver_url, crypto_store_path).await?;
+        client.restore_login((&session).into()).await?;
+
+        let own_user_id: OwnedUserId = session.user_id.as_str().try_into()?;
+        println!("restored session for {own_user_id}");
+
+        client.add_event_handler(on_stripped_state_member);
+
+        // Resume from wherever we left off, rather than a full initial sync,
+        // so we don't re-process messages we already relayed before a
+        // restart.
+        let sync_settings = match client.sync_token().await {
+            Some(token) => SyncSettings::default().token(token),
+            None => SyncSettings::default(),
+        };
+        client.sync_once(sync_settings).await?;
+
+        let mut rooms = HashMap::new();
+        let mut link_map = LinkMap::new();
+
+        for entry in &config.links {
+            for channel_str in &entry.rooms {
+                if let Some(irc_channel) = channel_str.strip_prefix("irc:") {
+                    link_map.add(entry.id.clone(), ChannelId::Irc(irc_channel.to_owned()));
+                    continue;
+                }
+
+                let Ok(room_id) = OwnedRoomId::try_from(channel_str.as_str()) else {
+                    eprintln!("Skipping invalid room id in config.toml: {channel_str}");
+                    continue;
+                };
+                let Some(room) = client.get_room(&room_id) else {
+                    eprintln!("Skipping link {}: not joined to {room_id}", entry.id);
+                    continue;
+                };
+                link_map.add(entry.id.clone(), ChannelId::Matrix(room_id.clone()));
+                rooms.insert(room_id, room);
+            }
+
+            for channel_str in &entry.sink_only {
+                if let Some(irc_channel) = channel_str.strip_prefix("irc:") {
+                    link_map.mark_sink_only(entry.id.clone(), ChannelId::Irc(irc_channel.to_owned()));
+                } else if let Ok(room_id) = OwnedRoomId::try_from(channel_str.as_str()) {
+                    link_map.mark_sink_only(entry.id.clone(), ChannelId::Matrix(room_id));
+                }
+            }
+        }
+
+        let tor_suffix = config.tor_suffix.clone().unwrap_or_else(|| DEFAULT_TOR_SUFFIX.to_owned());
+
+        let tor_rooms = get_all_tor_rooms(&client, &tor_suffix).await?;
+        let room_groups = get_room_groups(&client, tor_rooms, &tor_suffix).await?;
+
+        for (clearnet_name, room_ids) in room_groups {
+            if room_ids.len() < 2 {
+                // a Tor room with no clearnet match (or vice versa): nothing
+                // to link yet
+                continue;
+            }
+
+            let link = format!("tor-mirror:{clearnet_name}");
+            for room_id in room_ids {
+                let Some(room) = get_or_join_room(&client, &room_id).await else {
+                    eprintln!("Skipping {room_id} in link {link}: couldn't join");
+                    continue;
+                };
+                link_map.add(link.clone(), ChannelId::Matrix(room_id.clone()));
+                rooms.insert(room_id, room);
+            }
+        }
+
+        let link_map = Arc::new(RwLock::new(link_map));
+
+        Ok((
+            Self {
+                client,
+                own_user_id,
+                rooms: Arc::new(Mutex::new(rooms)),
+                link_map: link_map.clone(),
+                config: Arc::new(Mutex::new(config)),
+                config_path,
+            },
+            link_map,
+        ))
+    }
+}
+
+/// Writes the current `LinkMap` membership back into `config.toml` so links
+/// created with `!relay` survive a restart. IRC channels round-trip as
+/// `irc:{channel}` entries, matching the prefix `connect` looks for when it
+/// rebuilds the `LinkMap` on startup.
+async fn persist_links(
+    link_map: &RwLock<LinkMap<ChannelId>>,
+    config: &Mutex<Config>,
+    config_path: &Path,
+) {
+    let link_map = link_map.read().await;
+    let links: Vec<LinkEntry> = link_map
+        .entries()
+        .map(|(id, channels)| LinkEntry {
+            id: id.to_owned(),
+            rooms: channels.iter().filter_map(channel_to_string).collect(),
+            sink_only: channels
+                .iter()
+                .filter(|channel| link_map.is_sink_only(id, channel))
+                .filter_map(channel_to_string)
+                .collect(),
+        })
+        .collect();
+    drop(link_map);
+
+    let mut config = config.lock().await;
+    config.links = links;
+    if let Err(err) = config.save(config_path) {
+        eprintln!("Failed to persist links to {}: {err:?}", config_path.display());
+    }
+}
+
+/// Renders a `ChannelId` the same way `config.links` entries are written:
+/// a bare room id for Matrix, `irc:{channel}` for IRC.
+fn channel_to_string(channel: &ChannelId) -> Option<String> {
+    match channel {
+        ChannelId::Matrix(room_id) => Some(room_id.to_string()),
+        ChannelId::Irc(name) => Some(format!("irc:{name}")),
+    }
+}
+
+/// Builds a client pointed at `homeserver_url` with a sled-backed crypto
+/// store at `crypto_store_path`, so room keys and the sync token survive
+/// restarts and encrypted rooms are readable.
+async fn build_client(homeserver_url: &str, crypto_store_path: &Path) -> anyhow::Result<Client> {
+    let homeserver_url = Url::parse(homeserver_url).expect("Couldn't parse the homeserver URL");
+    let client = Client::builder()
+        .homeserver_url(homeserver_url)
+        .sled_crypto_store(crypto_store_path, None)
+        .await?
+        .build()
+        .await?;
+    Ok(client)
+}
+
+#[async_trait]
+impl Task for MatrixTask {
+    async fn start(
+        &self,
+        id: TaskId,
+        tx: mpsc::Sender<Message>,
+        mut rx: mpsc::Receiver<Message>,
+    ) -> anyhow::Result<()> {
+        let own_user_id = self.own_user_id.clone();
+        let rooms = self.rooms.clone();
+        let link_map = self.link_map.clone();
+        let config = self.config.clone();
+        let config_path = self.config_path.clone();
+        self.client.add_event_handler({
+            move |ev: OriginalSyncRoomMessageEvent, room: Room| {
+                let own_user_id = own_user_id.clone();
+                let tx = tx.clone();
+                let rooms = rooms.clone();
+                let link_map = link_map.clone();
+                let config = config.clone();
+                let config_path = config_path.clone();
+                async move {
+                    on_room_message(ev, room, own_user_id, tx, rooms, link_map, config, config_path).await;
+                }
+            }
+        });
+
+        let settings = match self.client.sync_token().await {
+            Some(token) => SyncSettings::default().token(token),
+            None => SyncSettings::default(),
+        };
+        let sync_client = self.client.clone();
+        tokio::spawn(async move {
+            sync_client.sync(settings).await;
+        });
+
+        // Deliver messages routed to us from other tasks by sending them
+        // into whichever room they're addressed to.
+        while let Some(message) = rx.recv().await {
+            let ChannelId::Matrix(room_id) = &message.origin else {
+                continue;
+            };
+            let rooms = self.rooms.lock().await;
+            let Some(Room::Joined(room)) = rooms.get(room_id) else {
+                continue;
+            };
+
+            let result = match &message.attachment {
+                Some(attachment) => {
+                    send_attachment(room, &message.sender, &message.body, attachment).await
+                }
+                None => {
+                    let content = RoomMessageEventContent::text_plain(format!(
+                        "{}: {}",
+                        message.sender, message.body
+                    ));
+                    room.send(content, None).await.map(|_| ())
+                }
+            };
+
+            if let Err(err) = result {
+                eprintln!("[{id}] Failed to send to room {room_id}: {err:?}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Re-uploads `attachment` to `room`'s homeserver and sends it, attributed
+/// to `sender` via its caption, since the original `mxc://` URI only
+/// resolves on the homeserver it was uploaded to.
+async fn send_attachment(
+    room: &matrix_sdk::room::Joined,
+    sender: &str,
+    fallback_body: &str,
+    attachment: &Attachment,
+) -> matrix_sdk::Result<()> {
+    let caption = format!("{sender}: {fallback_body}");
+    let mimetype: mime::Mime = attachment
+        .mimetype
+        .as_deref()
+        .unwrap_or("application/octet-stream")
+        .parse()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    room.send_attachment(
+        &caption,
+        &mimetype,
+        attachment.data.clone(),
+        AttachmentConfig::new(),
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Translates an incoming Matrix event into an `Event`, asks `decide` what
+/// to do about it, and performs the resulting `Action`s. Media and the
+/// `!relay`/`!unrelay` commands still need real I/O (downloading a file,
+/// checking power levels) so they bypass `decide` and keep talking to the
+/// SDK directly; everything else (plain-text fan-out and unknown commands)
+/// goes through the same pure routing logic the tests exercise.
+#[allow(clippy::too_many_arguments)]
+async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    own_user_id: OwnedUserId,
+    tx: mpsc::Sender<Message>,
+    rooms: Arc<Mutex<HashMap<OwnedRoomId, Room>>>,
+    link_map: Arc<RwLock<LinkMap<ChannelId>>>,
+    config: Arc<Mutex<Config>>,
+    config_path: PathBuf,
+) {
+    let Room::Joined(tx_room) = room else {
+        return;
+    };
+
+    let OriginalSyncRoomMessageEvent {
+        content: RoomMessageEventContent { msgtype, .. },
+        sender,
+        ..
+    } = event;
+
+    if sender == own_user_id {
+        // don't echo our own messages back into the link
+        return;
+    }
+
+    let Ok(Some(member)) = tx_room.get_member(&sender).await else {
+        return;
+    };
+    let name = member
+        .display_name()
+        .unwrap_or_else(|| member.user_id().as_str())
+        .to_owned();
+
+    let msg_body = match msgtype {
+        MessageType::Text(TextMessageEventContent { body, .. }) => body,
+        MessageType::Image(content) => {
+            let mimetype = content.info.as_ref().and_then(|info| info.mimetype.clone());
+            return relay_media(&tx_room, name, content.source, mimetype, content.body, AttachmentKind::Image, tx, rooms, link_map).await;
+        }
+        MessageType::File(content) => {
+            let mimetype = content.info.as_ref().and_then(|info| info.mimetype.clone());
+            return relay_media(&tx_room, name, content.source, mimetype, content.body, AttachmentKind::File, tx, rooms, link_map).await;
+        }
+        MessageType::Audio(content) => {
+            let mimetype = content.info.as_ref().and_then(|info| info.mimetype.clone());
+            return relay_media(&tx_room, name, content.source, mimetype, content.body, AttachmentKind::Audio, tx, rooms, link_map).await;
+        }
+        MessageType::Video(content) => {
+            let mimetype = content.info.as_ref().and_then(|info| info.mimetype.clone());
+            return relay_media(&tx_room, name, content.source, mimetype, content.body, AttachmentKind::Video, tx, rooms, link_map).await;
+        }
+        // reactions, notices, etc: nothing sensible to relay
+        _ => return,
+    };
+
+    if let Some(command) = msg_body.strip_prefix('!') {
+        if matches!(command.split_whitespace().next(), Some("relay") | Some("unrelay")) {
+            let reply = handle_command(
+                command,
+                &tx_room,
+                &member,
+                rooms,
+                link_map,
+                config,
+                &config_path,
+            )
+            .await;
+            let _ = tx_room
+                .send(RoomMessageEventContent::text_plain(reply), None)
+                .await;
+            return;
+        }
+    }
+
+    let room_id = tx_room.room_id().to_owned();
+    let decided_body = match msg_body.strip_prefix('!') {
+        Some(_) => msg_body.clone(),
+        None => format!("{name}: {msg_body}"),
+    };
+    let decide_event = Event::Message {
+        room: room_id.clone(),
+        sender,
+        body: decided_body,
+    };
+    let actions = {
+        let link_map = link_map.read().await;
+        decide(&decide_event, &link_map, &own_user_id)
+    };
+    execute_actions(actions, &rooms).await;
+
+    if msg_body.starts_with('!') {
+        return;
+    }
+
+    let _ = tx
+        .send(Message {
+            origin: ChannelId::Matrix(room_id),
+            link: tx_room.room_id().to_string(),
+            body: msg_body,
+            sender: name,
+            attachment: None,
+        })
+        .await;
+}
+
+/// Performs the `Action`s `decide` returned. `SendMessage` is delivered via
+/// whichever room this task has joined with that id; `AcceptInvite` never
+/// appears here (only `on_stripped_state_member` ever produces one).
+async fn execute_actions(actions: Vec<Action>, rooms: &Mutex<HashMap<OwnedRoomId, Room>>) {
+    for action in actions {
+        let Action::SendMessage(room_id, body) = action else {
+            continue;
+        };
+        let rooms = rooms.lock().await;
+        let Some(Room::Joined(room)) = rooms.get(&room_id) else {
+            continue;
+        };
+        if let Err(err) = room.send(RoomMessageEventContent::text_plain(body), None).await {
+            eprintln!("Failed to send to room {room_id}: {err:?}");
+        }
+    }
+}
+
+/// Dispatches the `!relay`/`!unrelay` commands typed in a linked room; any
+/// other command is left to `event::decide` and never reaches this.
+/// Only room moderators (power level >= `ADMIN_POWER_LEVEL`) may create or
+/// remove links, since `!relay` can pull messages from any room the bot has
+/// joined into this one.
+#[allow(clippy::too_many_arguments)]
+async fn handle_command(
+    command: &str,
+    tx_room: &matrix_sdk::room::Joined,
+    sender: &RoomMember,
+    rooms: Arc<Mutex<HashMap<OwnedRoomId, Room>>>,
+    link_map: Arc<RwLock<LinkMap<ChannelId>>>,
+    config: Arc<Mutex<Config>>,
+    config_path: &Path,
+) -> String {
+    let mut parts = command.split_whitespace();
+    let name = parts.next().expect("caller only dispatches known commands");
+
+    match name {
+        "relay" => {
+            if sender.power_level() < ADMIN_POWER_LEVEL {
+                return "You need to be a room moderator to use !relay.".to_owned();
+            }
+            let Some(target) = parts.next() else {
+                return "Usage: !relay <other-room-id-or-alias> [--two-way]".to_owned();
+            };
+            let two_way = match parts.next() {
+                Some("--two-way") => true,
+                Some(extra) => return format!("Unrecognized argument to !relay: {extra}"),
+                None => false,
+            };
+            relay_command(tx_room, target, two_way, rooms, link_map, config, config_path).await
+        }
+        "unrelay" => {
+            if sender.power_level() < ADMIN_POWER_LEVEL {
+                return "You need to be a room moderator to use !unrelay.".to_owned();
+            }
+            unrelay_command(tx_room, link_map, config, config_path).await
+        }
+        _ => unreachable!("caller only dispatches known commands"),
+    }
+}
+
+/// Links `tx_room` to `target` (a room id or `#alias`), joining it to the
+/// same link. By default the link is one-way, tx_room to target: target
+/// receives everything sent in tx_room, but tx_room won't receive target's
+/// messages back. `two_way` makes it symmetric instead.
+#[allow(clippy::too_many_arguments)]
+async fn relay_command(
+    tx_room: &matrix_sdk::room::Joined,
+    target: &str,
+    two_way: bool,
+    rooms: Arc<Mutex<HashMap<OwnedRoomId, Room>>>,
+    link_map: Arc<RwLock<LinkMap<ChannelId>>>,
+    config: Arc<Mutex<Config>>,
+    config_path: &Path,
+) -> String {
+    let client = tx_room.client();
+
+    let target_room_id = match resolve_room(&client, target).await {
+        Ok(room_id) => room_id,
+        Err(err) => return format!("Couldn't resolve {target}: {err}"),
+    };
+
+    let Some(target_room) = client.get_room(&target_room_id) else {
+        return format!("I haven't joined {target_room_id}; invite me there first.");
+    };
+
+    let link = format!("{}<->{target_room_id}", tx_room.room_id());
+    {
+        let mut link_map = link_map.write().await;
+        link_map.add(link.clone(), ChannelId::Matrix(tx_room.room_id().to_owned()));
+        link_map.add(link.clone(), ChannelId::Matrix(target_room_id.clone()));
+        if !two_way {
+            link_map.mark_sink_only(link, ChannelId::Matrix(target_room_id.clone()));
+        }
+    }
+    rooms.lock().await.insert(target_room_id.clone(), target_room);
+
+    persist_links(&link_map, &config, config_path).await;
+
+    if two_way {
+        format!("Linked {} <-> {target_room_id}", tx_room.room_id())
+    } else {
+        format!("Linked {} -> {target_room_id}", tx_room.room_id())
+    }
+}
+
+/// Removes `tx_room` from every link it's currently a member of.
+async fn unrelay_command(
+    tx_room: &matrix_sdk::room::Joined,
+    link_map: Arc<RwLock<LinkMap<ChannelId>>>,
+    config: Arc<Mutex<Config>>,
+    config_path: &Path,
+) -> String {
+    let channel = ChannelId::Matrix(tx_room.room_id().to_owned());
+
+    let removed = {
+        let mut link_map = link_map.write().await;
+        let links: Vec<String> = link_map
+            .entries()
+            .filter(|(_, channels)| channels.contains(&channel))
+            .map(|(id, _)| id.to_owned())
+            .collect();
+        for link in &links {
+            link_map.remove(link, &channel);
+        }
+        links.len()
+    };
+
+    if removed == 0 {
+        return format!("{} isn't linked to anything.", tx_room.room_id());
+    }
+
+    persist_links(&link_map, &config, config_path).await;
+    format!("Unlinked {} from {removed} link(s).", tx_room.room_id())
+}
+
+/// Resolves a `!relay` target, either a raw room id (`!abc:example.org`) or
+/// a published alias (`#room:example.org`).
+async fn resolve_room(client: &Client, target: &str) -> anyhow::Result<OwnedRoomId> {
+    if target.starts_with('#') {
+        let alias = <&RoomAliasId>::try_from(target)?;
+        let response = client.resolve_room_alias(alias).await?;
+        Ok(response.room_id)
+    } else {
+        Ok(OwnedRoomId::try_from(target)?)
+    }
+}
+
+/// Downloads an image/file/audio/video from the source homeserver and
+/// delivers it to every linked room. Matrix peers are delivered directly
+/// (re-uploading the content to each destination homeserver, the same as
+/// `send_attachment` does for the bus-routed path), mirroring how `decide`/
+/// `execute_actions` handle same-protocol text; cross-protocol peers still
+/// go through the supervisor's bus, since only it knows how to reach them.
+/// Falls back to a plain-text line with a direct download link if the media
+/// can't be fetched from here (e.g. it's encrypted and we don't hold the
+/// keys).
+#[allow(clippy::too_many_arguments)]
+async fn relay_media(
+    tx_room: &matrix_sdk::room::Joined,
+    sender: String,
+    source: MediaSource,
+    mimetype: Option<String>,
+    filename: String,
+    kind: AttachmentKind,
+    tx: mpsc::Sender<Message>,
+    rooms: Arc<Mutex<HashMap<OwnedRoomId, Room>>>,
+    link_map: Arc<RwLock<LinkMap<ChannelId>>>,
+) {
+    let (body, attachment) = match download_media(tx_room.client(), &source).await {
+        Some(data) => (
+            format!("sent a file: {filename}"),
+            Some(Attachment {
+                kind,
+                filename: filename.clone(),
+                mimetype,
+                data,
+            }),
+        ),
+        None => (
+            format!("sent a file we couldn't fetch: {filename} ({})", download_link(&source)),
+            None,
+        ),
+    };
+
+    let origin = ChannelId::Matrix(tx_room.room_id().to_owned());
+    let peers = link_map.read().await.peers_of(&origin);
+
+    for peer in &peers {
+        let ChannelId::Matrix(peer_room_id) = peer else {
+            continue;
+        };
+        let result = {
+            let rooms = rooms.lock().await;
+            let Some(Room::Joined(peer_room)) = rooms.get(peer_room_id) else {
+                continue;
+            };
+            match &attachment {
+                Some(attachment) => send_attachment(peer_room, &sender, &body, attachment).await,
+                None => peer_room
+                    .send(RoomMessageEventContent::text_plain(format!("{sender}: {body}")), None)
+                    .await
+                    .map(|_| ()),
+            }
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to relay media to room {peer_room_id}: {err:?}");
+        }
+    }
+
+    let _ = tx
+        .send(Message {
+            origin,
+            link: tx_room.room_id().to_string(),
+            body,
+            sender,
+            attachment,
+        })
+        .await;
+}
+
+async fn download_media(client: &Client, source: &MediaSource) -> Option<Vec<u8>> {
+    let request = MediaRequest {
+        source: source.clone(),
+        format: MediaFormat::File,
+    };
+    client.media().get_media_content(&request, true).await.ok()
+}
+
+/// A direct HTTP download link for media that we couldn't (or don't want
+/// to) fetch and re-upload ourselves, in the form the client-server spec
+/// defines for downloading by `mxc://` server/media-id
+/// (`/_matrix/media/r0/download/{server}/{media_id}`), not just the raw
+/// `mxc://` URI Display, which isn't a fetchable URL.
+fn download_link(source: &MediaSource) -> String {
+    let uri = match source {
+        MediaSource::Plain(uri) => uri,
+        MediaSource::Encrypted(file) => &file.url,
+    };
+    match uri.parts() {
+        Ok((server_name, media_id)) => {
+            format!("https://{server_name}/_matrix/media/r0/download/{server_name}/{media_id}")
+        }
+        Err(_) => uri.to_string(),
+    }
+}
+
+// Whenever we see a new stripped room member event, we've asked our client
+// to call this function. So what exactly are we doing then?
+async fn on_stripped_state_member(room_member: StrippedRoomMemberEvent, client: Client, room: Room) {
+    if room_member.state_key != client.user_id().unwrap() {
+        // the invite we've seen isn't for us, but for someone else. ignore
+        return;
+    }
+
+    // looks like the room is an invited room, let's attempt to join then
+    if let Room::Invited(room) = room {
+        let own_user_id = client.user_id().unwrap().to_owned();
+        let event = Event::Invitation(room.room_id().to_owned());
+        // `decide` never looks at the link map for an invitation, so an
+        // empty one is fine here; this just keeps the decision itself
+        // shared with what the tests exercise.
+        for action in decide(&event, &LinkMap::new(), &own_user_id) {
+            execute_invite_action(action, &room).await;
+        }
+    }
+}
+
+async fn execute_invite_action(action: Action, room: &matrix_sdk::room::Invited) {
+    let Action::AcceptInvite(_) = action else {
+        return;
+    };
+
+    println!("Autojoining room {}", room.room_id());
+    retry_join(room.room_id(), || room.accept_invitation()).await;
+}
+
+/// Retries `attempt` with exponential backoff (waiting 2s, then doubling),
+/// logging and giving up once the next wait would exceed one hour. Synapse can send an
+/// invite before the invited user is actually able to join yet (see
+/// https://github.com/matrix-org/synapse/issues/4345), and the same race
+/// applies to auto-joining a Tor/clearnet mirror we haven't joined yet.
+async fn retry_join<F, Fut>(room_id: &RoomId, mut attempt: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = matrix_sdk::Result<()>>,
+{
+    let mut delay = 2;
+
+    while let Err(err) = attempt().await {
+        eprintln!("Failed to join room {room_id} ({err:?}), retrying in {delay}s");
+
+        sleep(Duration::from_secs(delay)).await;
+        delay *= 2;
+
+        if delay > 3600 {
+            eprintln!("Can't join room {room_id} ({err:?})");
+            return;
+        }
+    }
+    println!("Successfully joined room {room_id}");
+}
+
+/// Returns the room we've already joined with id `room_id`, or requests to
+/// join it (retrying with backoff) and returns the result once that
+/// succeeds. Used for Tor/clearnet pairing, where the bot may not have
+/// joined every mirror yet.
+async fn get_or_join_room(client: &Client, room_id: &RoomId) -> Option<Room> {
+    if let Some(room) = client.get_room(room_id) {
+        return Some(room);
+    }
+
+    println!("Not joined to {room_id} yet, requesting to join");
+    retry_join(room_id, || async {
+        client.join_room_by_id(room_id).await.map(|_| ())
+    })
+    .await;
+
+    client.get_room(room_id)
+}
+
+async fn get_all_tor_rooms(
+    client: &Client,
+    tor_suffix: &str,
+) -> matrix_sdk::Result<Vec<(OwnedRoomId, String)>, Error> {
+    use matrix_sdk::ruma::{api::client::directory::get_public_rooms_filtered, directory::Filter};
+
+    println!("Searching for rooms whose name contains '{tor_suffix}'");
+
+    let mut filter = Filter::new();
+    filter.generic_search_term = Some(tor_suffix);
+    let mut request = get_public_rooms_filtered::v3::Request::new();
+    request.filter = filter;
+
+    let response = client.public_rooms_filtered(request).await?;
+
+    let mut tor_room_ids: Vec<(OwnedRoomId, String)> = Vec::new();
+
+    for public_rooms_chunk in response.chunk {
+        println!("Found room {:?}", public_rooms_chunk.name);
+        tor_room_ids.push((public_rooms_chunk.room_id, public_rooms_chunk.name.unwrap()));
+    }
+
+    Ok(tor_room_ids)
+}
+
+/// Default marker that, stripped off the end of a room name
+/// case-insensitively, turns it into the clearnet name it mirrors (e.g. "My
+/// Room (Tor)" mirrors "My Room"). Used when `Config::tor_suffix` isn't set;
+/// deployments that tag mirrors differently can override it there.
+const DEFAULT_TOR_SUFFIX: &str = "(Tor)";
+
+/// Strips `suffix` off the end of `room_name`, case-insensitively, if it's
+/// there. Returns `None` for rooms that only matched the homeserver's fuzzy
+/// search without actually ending in the marker.
+///
+/// Compares against `trimmed` itself rather than a lowercased copy: matching
+/// on a `to_lowercase()`'d string and then slicing the original by its byte
+/// length breaks whenever lowercasing changes the byte length (e.g. Turkish
+/// `İ`), since the index no longer lines up with a char boundary in
+/// `trimmed`.
+fn clearnet_name_for(room_name: &str, suffix: &str) -> Option<String> {
+    let trimmed = room_name.trim_end();
+    let suffix_start = trimmed.len().checked_sub(suffix.len())?;
+    if !trimmed.is_char_boundary(suffix_start) {
+        return None;
+    }
+
+    let (prefix, room_suffix) = trimmed.split_at(suffix_start);
+    if !room_suffix.eq_ignore_ascii_case(suffix) {
+        return None;
+    }
+
+    Some(prefix.trim_end().to_owned())
+}
+
+/// Groups every Tor room together with the clearnet room(s) it mirrors, by
+/// matching on `clearnet_name_for` with `tor_suffix` (see
+/// `Config::tor_suffix`). Unlike the exact-pair matching this replaces, a
+/// clearnet room can end up grouped with several Tor mirrors (or vice
+/// versa): the group, keyed by clearnet name, is what becomes a single link
+/// in `LinkMap`.
+async fn get_room_groups(
+    client: &Client,
+    tor_rooms: Vec<(OwnedRoomId, String)>,
+    tor_suffix: &str,
+) -> matrix_sdk::Result<Vec<(String, Vec<OwnedRoomId>)>, Error> {
+    use matrix_sdk::ruma::{api::client::directory::get_public_rooms_filtered, directory::Filter};
+
+    let mut groups: HashMap<String, Vec<OwnedRoomId>> = HashMap::new();
+
+    for (room_id, room_name) in tor_rooms {
+        let Some(clearnet_name) = clearnet_name_for(&room_name, tor_suffix) else {
+            eprintln!("Skipping room {room_name:?}: doesn't end in {tor_suffix:?}");
+            continue;
+        };
+        println!("Searching for rooms whose name is '{clearnet_name}'");
+
+        groups.entry(clearnet_name.clone()).or_default().push(room_id);
+
+        let mut filter = Filter::new();
+        filter.generic_search_term = Some(clearnet_name.as_str());
+        let mut request = get_public_rooms_filtered::v3::Request::new();
+        request.filter = filter;
+
+        let response = client.public_rooms_filtered(request).await?;
+
+        if response.chunk.len() > 1 {
+            println!(
+                "NOTE: {} rooms matched clearnet name {clearnet_name}; linking every one named exactly that",
+                response.chunk.len()
+            );
+        }
+
+        for public_rooms_chunk in response.chunk {
+            println!("Found room {:?}", public_rooms_chunk.name);
+            if public_rooms_chunk.name.as_deref() != Some(clearnet_name.as_str()) {
+                continue;
+            }
+            let group = groups.entry(clearnet_name.clone()).or_default();
+            if !group.contains(&public_rooms_chunk.room_id) {
+                group.push(public_rooms_chunk.room_id);
+            }
+        }
+    }
+
+    Ok(groups.into_iter().collect())
+}
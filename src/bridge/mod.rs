@@ -0,0 +1,5 @@
+mod irc;
+mod matrix;
+
+pub use irc::IrcTask;
+pub use matrix::MatrixTask;
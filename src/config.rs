@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Settings loaded from `config.toml`. The homeserver is fixed at first
+/// login; everything else needed to resume a session lives in `session`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub homeserver_url: String,
+    pub session: Option<Session>,
+    /// Every link in the `LinkMap` as of the last `!relay`/`!unrelay` that
+    /// changed it (including discovered Tor-mirror groups), persisted so
+    /// links created at runtime survive a restart alongside the session.
+    #[serde(default)]
+    pub links: Vec<LinkEntry>,
+    /// Case-insensitive marker stripped off the end of a room name to find
+    /// the clearnet room it mirrors, e.g. `"(Tor)"`. Defaults to `"(Tor)"`
+    /// when unset; override it if a deployment tags its Tor mirrors
+    /// differently.
+    #[serde(default)]
+    pub tor_suffix: Option<String>,
+    /// IRC network to bridge, if any. Absent by default: the bot runs
+    /// Matrix-only until this is configured, since connecting to a network
+    /// nobody asked for would just mean a dangling connection.
+    #[serde(default)]
+    pub irc: Option<IrcSettings>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// A single `LinkMap` entry, persisted as the link id plus the channels it
+/// contains. Each entry in `rooms` is either a Matrix room id (aliases are
+/// not resolved here and are skipped on load), or an IRC channel written as
+/// `irc:{channel}` (e.g. `irc:#example`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkEntry {
+    pub id: String,
+    pub rooms: Vec<String>,
+    /// Channels within this link that only receive forwarded messages and
+    /// never forward their own, i.e. the non-`--two-way` side of a `!relay`.
+    /// Written using the same syntax as `rooms`.
+    #[serde(default)]
+    pub sink_only: Vec<String>,
+}
+
+/// A previously established login, persisted so the bot doesn't have to
+/// re-authenticate (and re-sync from scratch) on every restart, and so it
+/// can open the same crypto store it left off with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub access_token: String,
+    pub user_id: String,
+    pub device_id: String,
+}
+
+impl From<&Session> for matrix_sdk::Session {
+    fn from(session: &Session) -> Self {
+        matrix_sdk::Session {
+            access_token: session.access_token.clone(),
+            user_id: session
+                .user_id
+                .as_str()
+                .try_into()
+                .expect("invalid user_id persisted in config.toml"),
+            device_id: session.device_id.as_str().into(),
+        }
+    }
+}
+
+/// Settings for the optional IRC backend (`IrcTask`); a thin wrapper around
+/// what the `irc` crate's own `Config` needs, so `config.toml` doesn't have
+/// to expose that crate's full surface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IrcSettings {
+    pub server: String,
+    pub nickname: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub use_tls: Option<bool>,
+}
+
+impl From<&IrcSettings> for irc::client::prelude::Config {
+    fn from(settings: &IrcSettings) -> Self {
+        irc::client::prelude::Config {
+            nickname: Some(settings.nickname.clone()),
+            server: Some(settings.server.clone()),
+            port: settings.port,
+            use_tls: settings.use_tls,
+            ..Default::default()
+        }
+    }
+}
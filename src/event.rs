@@ -0,0 +1,175 @@
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+
+use crate::{linkmap::LinkMap, message::ChannelId};
+
+/// A Matrix occurrence translated out of SDK types, so routing logic never
+/// touches `matrix_sdk` directly and can run without awaiting anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Invitation(OwnedRoomId),
+    Message {
+        room: OwnedRoomId,
+        sender: OwnedUserId,
+        body: String,
+    },
+}
+
+/// What the executor should do in response to an `Event`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    AcceptInvite(OwnedRoomId),
+    SendMessage(OwnedRoomId, String),
+}
+
+/// All plain-message routing/command logic lives here, decoupled from any
+/// network I/O so it can be covered by plain synchronous tests. `!relay`/
+/// `!unrelay` aren't decided here: they mutate the `LinkMap` itself and need
+/// the live client to resolve aliases and check power levels, so the
+/// executor special-cases them before `decide` ever sees them.
+pub fn decide(event: &Event, link_map: &LinkMap<ChannelId>, own_user_id: &OwnedUserId) -> Vec<Action> {
+    match event {
+        Event::Invitation(room_id) => vec![Action::AcceptInvite(room_id.clone())],
+        Event::Message { room, sender, body } => {
+            if sender == own_user_id {
+                // don't echo our own messages back into the link
+                return Vec::new();
+            }
+
+            if let Some(command) = body.strip_prefix('!') {
+                return decide_command(command, room);
+            }
+
+            link_map
+                .peers_of(&ChannelId::Matrix(room.clone()))
+                .into_iter()
+                .filter_map(|peer| match peer {
+                    ChannelId::Matrix(peer_room) => {
+                        Some(Action::SendMessage(peer_room, body.clone()))
+                    }
+                    // cross-protocol peers are forwarded by the supervisor,
+                    // not resolved here
+                    ChannelId::Irc(_) => None,
+                })
+                .collect()
+        }
+    }
+}
+
+fn decide_command(command: &str, room: &OwnedRoomId) -> Vec<Action> {
+    match command.split_whitespace().next() {
+        // handled by the executor directly; nothing for us to decide
+        Some("relay") | Some("unrelay") => Vec::new(),
+        Some(name) => vec![Action::SendMessage(
+            room.clone(),
+            format!("Command not found: {name}"),
+        )],
+        None => vec![Action::SendMessage(
+            room.clone(),
+            "Command not found: (empty)".to_owned(),
+        )],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(id: &str) -> OwnedRoomId {
+        OwnedRoomId::try_from(id).unwrap()
+    }
+
+    fn user(id: &str) -> OwnedUserId {
+        OwnedUserId::try_from(id).unwrap()
+    }
+
+    #[test]
+    fn forwards_a_message_to_every_linked_room() {
+        let tor_room = room("!tor:example.org");
+        let clearnet_room = room("!clearnet:example.org");
+
+        let mut link_map = LinkMap::new();
+        link_map.add("link", ChannelId::Matrix(tor_room.clone()));
+        link_map.add("link", ChannelId::Matrix(clearnet_room.clone()));
+
+        let event = Event::Message {
+            room: tor_room,
+            sender: user("@alice:example.org"),
+            body: "hello".to_owned(),
+        };
+
+        let actions = decide(&event, &link_map, &user("@bot:example.org"));
+
+        assert_eq!(
+            actions,
+            vec![Action::SendMessage(clearnet_room, "hello".to_owned())]
+        );
+    }
+
+    #[test]
+    fn never_echoes_our_own_messages() {
+        let tor_room = room("!tor:example.org");
+        let clearnet_room = room("!clearnet:example.org");
+        let bot = user("@bot:example.org");
+
+        let mut link_map = LinkMap::new();
+        link_map.add("link", ChannelId::Matrix(tor_room.clone()));
+        link_map.add("link", ChannelId::Matrix(clearnet_room));
+
+        let event = Event::Message {
+            room: tor_room,
+            sender: bot.clone(),
+            body: "hello".to_owned(),
+        };
+
+        assert_eq!(decide(&event, &link_map, &bot), Vec::new());
+    }
+
+    #[test]
+    fn replies_to_an_unknown_command() {
+        let room_id = room("!tor:example.org");
+        let link_map = LinkMap::new();
+
+        let event = Event::Message {
+            room: room_id.clone(),
+            sender: user("@alice:example.org"),
+            body: "!frobnicate".to_owned(),
+        };
+
+        let actions = decide(&event, &link_map, &user("@bot:example.org"));
+
+        assert_eq!(
+            actions,
+            vec![Action::SendMessage(
+                room_id,
+                "Command not found: frobnicate".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn leaves_relay_and_unrelay_to_the_executor() {
+        let room_id = room("!tor:example.org");
+        let link_map = LinkMap::new();
+
+        let event = Event::Message {
+            room: room_id,
+            sender: user("@alice:example.org"),
+            body: "!relay #other:example.org".to_owned(),
+        };
+
+        assert_eq!(decide(&event, &link_map, &user("@bot:example.org")), Vec::new());
+    }
+
+    #[test]
+    fn always_accepts_invitations() {
+        let room_id = room("!invited:example.org");
+        let link_map = LinkMap::new();
+
+        let event = Event::Invitation(room_id.clone());
+
+        assert_eq!(
+            decide(&event, &link_map, &user("@bot:example.org")),
+            vec![Action::AcceptInvite(room_id)]
+        );
+    }
+}
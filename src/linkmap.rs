@@ -0,0 +1,81 @@
+use std::collections::{HashMap, HashSet};
+
+/// Maps an abstract link id to the channels it contains. A link can span any
+/// number of channels across any number of protocols, so pairing two rooms
+/// one-to-one (the old Tor/clearnet behavior) is just a link with two
+/// members; a relay fanning out to several mirrors is a link with more.
+///
+/// Members are bidirectional by default. A member can instead be marked
+/// "sink-only" within a link (see `mark_sink_only`), meaning it still
+/// receives everything forwarded through that link but never forwards its
+/// own messages back out through it — this is how `!relay` without
+/// `--two-way` gets one-way forwarding out of an otherwise symmetric
+/// structure.
+#[derive(Debug, Default, Clone)]
+pub struct LinkMap<C> {
+    links: HashMap<String, Vec<C>>,
+    sink_only: HashSet<(String, C)>,
+}
+
+impl<C: Clone + PartialEq + Eq + std::hash::Hash> LinkMap<C> {
+    pub fn new() -> Self {
+        Self {
+            links: HashMap::new(),
+            sink_only: HashSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, link: impl Into<String>, channel: C) {
+        let channels = self.links.entry(link.into()).or_default();
+        if !channels.contains(&channel) {
+            channels.push(channel);
+        }
+    }
+
+    /// Marks `channel` as receiving but never forwarding within `link`. Has
+    /// no effect unless `channel` is already a member of `link`.
+    pub fn mark_sink_only(&mut self, link: impl Into<String>, channel: C) {
+        let link = link.into();
+        if self.links.get(&link).map_or(false, |channels| channels.contains(&channel)) {
+            self.sink_only.insert((link, channel));
+        }
+    }
+
+    pub fn remove(&mut self, link: &str, channel: &C) {
+        if let Some(channels) = self.links.get_mut(link) {
+            channels.retain(|c| c != channel);
+        }
+        self.sink_only.retain(|(id, c)| id != link || c != channel);
+    }
+
+    /// Every other channel that shares a link with `channel`, excluding
+    /// links where `channel` is marked sink-only (it receives from those,
+    /// but doesn't forward into them).
+    pub fn peers_of(&self, channel: &C) -> Vec<C> {
+        self.links
+            .iter()
+            .filter(|(id, channels)| {
+                channels.contains(channel) && !self.sink_only.contains(&((*id).clone(), channel.clone()))
+            })
+            .flat_map(|(_, channels)| channels.iter().cloned())
+            .filter(|c| c != channel)
+            .collect()
+    }
+
+    /// Every channel that belongs to at least one link.
+    pub fn all_channels(&self) -> Vec<C> {
+        self.links.values().flatten().cloned().collect()
+    }
+
+    /// Every link id and the channels it currently contains, for
+    /// persistence or inspection.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &[C])> {
+        self.links.iter().map(|(id, channels)| (id.as_str(), channels.as_slice()))
+    }
+
+    /// Whether `channel` is marked sink-only within `link`, for persistence
+    /// or inspection.
+    pub fn is_sink_only(&self, link: &str, channel: &C) -> bool {
+        self.sink_only.contains(&(link.to_owned(), channel.clone()))
+    }
+}
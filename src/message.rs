@@ -0,0 +1,47 @@
+use matrix_sdk::ruma::OwnedRoomId;
+
+/// Identifies a single channel (a Matrix room, an IRC channel, ...) within
+/// whichever protocol backend owns it, so routing code never has to know
+/// which protocol it's dealing with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChannelId {
+    Matrix(OwnedRoomId),
+    Irc(String),
+}
+
+/// A message as it travels between protocol backends, stripped of any
+/// SDK-specific representation.
+#[derive(Debug, Clone)]
+pub struct Message {
+    /// The channel the message came from (or, once routed, the channel it's
+    /// being delivered to).
+    pub origin: ChannelId,
+    /// Set by the originating backend to the id of the channel the message
+    /// came from (a Matrix room id or an IRC channel name), not to a `LinkMap`
+    /// link id; the supervisor routes by `origin` and does not read this.
+    pub link: String,
+    /// Always set, even when `attachment` is present: a plain-text fallback
+    /// for backends that can't (or failed to) relay the attachment itself.
+    pub body: String,
+    pub sender: String,
+    pub attachment: Option<Attachment>,
+}
+
+/// Non-text content (an image, file, audio or video) carried alongside a
+/// `Message`, already downloaded from the source homeserver so the
+/// destination backend can re-upload it to wherever it's relaying to.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    pub filename: String,
+    pub mimetype: Option<String>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentKind {
+    Image,
+    File,
+    Audio,
+    Video,
+}
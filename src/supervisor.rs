@@ -0,0 +1,90 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::{mpsc, RwLock};
+
+use crate::{
+    linkmap::LinkMap,
+    message::{ChannelId, Message},
+    task::{Task, TaskId},
+};
+
+/// Owns every protocol backend and fans messages out across whichever
+/// channels the `LinkMap` says should hear from each other. Each backend
+/// (`MatrixTask`, `IrcTask`, ...) only ever sees the internal `Message`
+/// type, so the Tor<->clearnet pairing this bot started out as is just one
+/// particular shape of link.
+///
+/// The `LinkMap` lives behind an `RwLock` shared with whichever task owns
+/// it, so commands like `!relay`/`!unrelay` can mutate it at runtime and
+/// have the fan-out loop pick the change up on the next message.
+pub struct Supervisor {
+    link_map: Arc<RwLock<LinkMap<ChannelId>>>,
+    inboxes: HashMap<TaskId, mpsc::Sender<Message>>,
+}
+
+impl Supervisor {
+    pub fn new(link_map: Arc<RwLock<LinkMap<ChannelId>>>) -> Self {
+        Self {
+            link_map,
+            inboxes: HashMap::new(),
+        }
+    }
+
+    /// Spawns `task`'s connection loop, wiring it into the fan-out under
+    /// `id`. `outbox` is the shared sender the task uses to push messages it
+    /// receives from its protocol back to the supervisor.
+    pub fn spawn(&mut self, id: impl Into<TaskId>, task: Arc<dyn Task>, outbox: mpsc::Sender<Message>) {
+        let id = id.into();
+        let (inbox_tx, inbox_rx) = mpsc::channel(64);
+        self.inboxes.insert(id.clone(), inbox_tx);
+
+        tokio::spawn(async move {
+            if let Err(err) = task.start(id.clone(), outbox, inbox_rx).await {
+                eprintln!("Task {id} exited: {err:?}");
+            }
+        });
+    }
+
+    /// Runs the central fan-out loop: every `Message` pushed onto `rx` by a
+    /// task gets forwarded to every other channel sharing a link with its
+    /// origin, delivered via whichever task owns that channel.
+    ///
+    /// Matrix-to-Matrix peers are skipped: the Matrix backend resolves those
+    /// locally (see `event::decide`) so it can reply synchronously instead of
+    /// round-tripping through this loop. Every other peer, including IRC
+    /// channels linked to another IRC channel, is delivered from here.
+    pub async fn run(&self, mut rx: mpsc::Receiver<Message>) {
+        while let Some(message) = rx.recv().await {
+            let peers = self.link_map.read().await.peers_of(&message.origin);
+            for peer in peers {
+                if delivered_locally(&message.origin, &peer) {
+                    continue;
+                }
+                let Some(inbox) = self.inboxes.get(&task_for(&peer)) else {
+                    continue;
+                };
+                let _ = inbox
+                    .send(Message {
+                        origin: peer,
+                        ..message.clone()
+                    })
+                    .await;
+            }
+        }
+    }
+}
+
+/// Whether the backend that owns `origin` has already delivered to `peer`
+/// itself, so the supervisor must not deliver it a second time.
+fn delivered_locally(origin: &ChannelId, peer: &ChannelId) -> bool {
+    matches!((origin, peer), (ChannelId::Matrix(_), ChannelId::Matrix(_)))
+}
+
+/// Every channel belongs to exactly one protocol backend, so the task id
+/// that owns a channel follows directly from which protocol it's on.
+fn task_for(channel: &ChannelId) -> TaskId {
+    match channel {
+        ChannelId::Matrix(_) => "matrix".to_owned(),
+        ChannelId::Irc(_) => "irc".to_owned(),
+    }
+}
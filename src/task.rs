@@ -0,0 +1,20 @@
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use crate::message::Message;
+
+pub type TaskId = String;
+
+/// A protocol backend that owns a single connection loop (a Matrix sync
+/// loop, an IRC read loop, ...). The supervisor hands it a channel to push
+/// `Message`s onto and a channel to receive `Message`s that were routed to
+/// it from other tasks.
+#[async_trait]
+pub trait Task: Send + Sync {
+    async fn start(
+        &self,
+        id: TaskId,
+        tx: mpsc::Sender<Message>,
+        rx: mpsc::Receiver<Message>,
+    ) -> anyhow::Result<()>;
+}